@@ -37,8 +37,9 @@ pub struct BindSource {
 
 pub type BindingMap = FastHashMap<BindSource, BindTarget>;
 
+#[derive(Clone, Debug, PartialEq)]
 enum ResolvedBinding {
-    BuiltIn(spirv::BuiltIn),
+    BuiltIn(&'static str),
     Attribute(spirv::Word),
     Color(spirv::Word),
     User { prefix: &'static str, index: spirv::Word },
@@ -49,9 +50,23 @@ enum ResolvedBinding {
 pub enum Error {
     Format(FmtError),
     UnsupportedExecutionModel(spirv::ExecutionModel),
-    MixedExecutionModels(crate::Token<crate::Function>),
-    MissingBinding(crate::Token<crate::GlobalVariable>),
-    MissingBindTarget(BindSource),
+    MixedExecutionModels {
+        function: String,
+        first: spirv::ExecutionModel,
+        second: spirv::ExecutionModel,
+    },
+    MissingBinding {
+        global: String,
+        member: String,
+    },
+    MissingBindTarget {
+        source: BindSource,
+        available: Vec<BindSource>,
+    },
+    UnsupportedBuiltIn {
+        built_in: spirv::BuiltIn,
+        context: &'static str,
+    },
     BadName(String),
 }
 
@@ -61,6 +76,65 @@ impl From<FmtError> for Error {
     }
 }
 
+impl Display for Error {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> Result<(), FmtError> {
+        match *self {
+            Error::Format(ref e) => write!(formatter, "{}", e),
+            Error::UnsupportedExecutionModel(model) => write!(
+                formatter,
+                "execution model {:?} is not supported by the Metal backend",
+                model,
+            ),
+            Error::MixedExecutionModels { ref function, first, second } => write!(
+                formatter,
+                "function `{}` is used as an entry point with both {:?} and {:?} execution models; \
+                 Metal requires a function to be specialized for a single stage",
+                function, first, second,
+            ),
+            Error::MissingBinding { ref global, ref member } => write!(
+                formatter,
+                "global variable `{}` has output member `{}` with no `[[...]]` binding; \
+                 every member lifted into the root output struct needs one",
+                global, member,
+            ),
+            Error::MissingBindTarget { ref source, ref available } => {
+                write!(
+                    formatter,
+                    "no bind target mapped for set {} binding {}",
+                    source.set, source.binding,
+                )?;
+                if available.is_empty() {
+                    write!(formatter, " (the binding map is empty)")
+                } else {
+                    write!(formatter, "; bindings present in the map are: ")?;
+                    for (i, source) in available.iter().enumerate() {
+                        if i != 0 {
+                            write!(formatter, ", ")?;
+                        }
+                        write!(formatter, "(set {}, binding {})", source.set, source.binding)?;
+                    }
+                    Ok(())
+                }
+            }
+            Error::UnsupportedBuiltIn { built_in, context } => write!(
+                formatter,
+                "built-in {:?} has no MSL equivalent as {}",
+                built_in, context,
+            ),
+            Error::BadName(ref name) => write!(formatter, "`{}` is not a valid MSL identifier", name),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            Error::Format(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 enum LocationMode {
     VertexInput,
@@ -68,6 +142,46 @@ enum LocationMode {
     Intermediate,
 }
 
+fn location_mode_context(mode: LocationMode) -> &'static str {
+    match mode {
+        LocationMode::VertexInput => "a vertex input",
+        LocationMode::FragmentOutput => "a fragment output",
+        LocationMode::Intermediate => "an intermediate stage-to-stage value",
+    }
+}
+
+/// The SPIR-V built-in → MSL attribute table. Only the built-ins listed
+/// below are mapped; any other built-in, or a listed one bound where it is
+/// not listed, resolves to `Error::UnsupportedBuiltIn`. A built-in's spelling
+/// (and whether it's even legal) depends on where it's bound: e.g. `Position`
+/// is the vertex function's return value but a fragment function's input,
+/// while `FragDepth` only exists as a fragment output.
+fn resolve_builtin_name(built_in: spirv::BuiltIn, mode: LocationMode) -> Result<&'static str, Error> {
+    use spirv::BuiltIn as Bi;
+    use LocationMode as Lm;
+    let name = match (built_in, mode) {
+        (Bi::Position, Lm::VertexInput) | (Bi::Position, Lm::Intermediate) => "position",
+        (Bi::FragCoord, Lm::Intermediate) => "position",
+        (Bi::PointSize, Lm::Intermediate) => "point_size",
+        (Bi::ClipDistance, Lm::Intermediate) => "clip_distance",
+        (Bi::FragDepth, Lm::FragmentOutput) => "depth(any)",
+        (Bi::FrontFacing, Lm::Intermediate) => "front_facing",
+        (Bi::SampleId, Lm::Intermediate) => "sample_id",
+        (Bi::SampleMask, Lm::Intermediate) => "sample_mask",
+        (Bi::VertexIndex, Lm::VertexInput) => "vertex_id",
+        (Bi::InstanceIndex, Lm::VertexInput) => "instance_id",
+        (Bi::GlobalInvocationId, Lm::Intermediate) => "thread_position_in_grid",
+        (Bi::WorkgroupId, Lm::Intermediate) => "threadgroup_position_in_grid",
+        (Bi::LocalInvocationId, Lm::Intermediate) => "thread_index_in_threadgroup",
+        (Bi::NumWorkgroups, Lm::Intermediate) => "threadgroups_per_grid",
+        _ => return Err(Error::UnsupportedBuiltIn {
+            built_in,
+            context: location_mode_context(mode),
+        }),
+    };
+    Ok(name)
+}
+
 pub struct Options<'a> {
     pub binding_map: &'a BindingMap,
 }
@@ -75,7 +189,9 @@ pub struct Options<'a> {
 impl Options<'_> {
     fn resolve_binding(&self, binding: &crate::Binding, mode: LocationMode) -> Result<ResolvedBinding, Error> {
         match *binding {
-            crate::Binding::BuiltIn(built_in) => Ok(ResolvedBinding::BuiltIn(built_in)),
+            crate::Binding::BuiltIn(built_in) => {
+                resolve_builtin_name(built_in, mode).map(ResolvedBinding::BuiltIn)
+            }
             crate::Binding::Location(index) => Ok(match mode {
                 LocationMode::VertexInput => ResolvedBinding::Attribute(index),
                 LocationMode::FragmentOutput => ResolvedBinding::Color(index),
@@ -90,8 +206,11 @@ impl Options<'_> {
                     .get(&source)
                     .cloned()
                     .map(ResolvedBinding::Resource)
-                    .ok_or(Error::MissingBindTarget(source))
-
+                    .ok_or_else(|| {
+                        let mut available: Vec<_> = self.binding_map.keys().cloned().collect();
+                        available.sort();
+                        Error::MissingBindTarget { source, available }
+                    })
             }
         }
     }
@@ -124,6 +243,10 @@ impl Indexed for crate::Token<crate::Function> {
     const CLASS: &'static str = "function";
     fn id(&self) -> usize { self.index() }
 }
+impl Indexed for crate::Token<crate::LocalVariable> {
+    const CLASS: &'static str = "local";
+    fn id(&self) -> usize { self.index() }
+}
 
 struct MemberIndex(usize);
 impl Indexed for MemberIndex {
@@ -266,15 +389,7 @@ impl Display for TypedGlobalVariable<'_> {
 impl Display for ResolvedBinding {
     fn fmt(&self, formatter: &mut Formatter<'_>) -> Result<(), FmtError> {
         match *self {
-            ResolvedBinding::BuiltIn(built_in) => {
-                let name = match built_in {
-                    spirv::BuiltIn::ClipDistance => "clip_distance",
-                    spirv::BuiltIn::PointSize => "point_size",
-                    spirv::BuiltIn::Position => "position",
-                    _ => panic!("Built in {:?} is not implemented", built_in),
-                };
-                formatter.write_str(name)
-            }
+            ResolvedBinding::BuiltIn(name) => formatter.write_str(name),
             ResolvedBinding::Attribute(index) => {
                 write!(formatter, "attribute({})", index)
             }
@@ -299,10 +414,79 @@ impl Display for ResolvedBinding {
     }
 }
 
+/// Resource usage of a single entry point, as actually emitted by the
+/// writer: which descriptor-set bindings it referenced (and what Metal
+/// slot each was resolved to), and the location/built-in assignments of
+/// its stage-in and stage-out members.
+///
+/// Built-ins lifted out of a struct global (see the module docs) are not
+/// represented here, since they don't map back to a single `GlobalVariable`.
+#[derive(Debug)]
+pub struct EntryPointResources {
+    pub execution_model: spirv::ExecutionModel,
+    pub resources: Vec<(BindSource, BindTarget)>,
+    pub inputs: FastHashMap<crate::Token<crate::GlobalVariable>, ResolvedBinding>,
+    pub outputs: FastHashMap<crate::Token<crate::GlobalVariable>, ResolvedBinding>,
+}
+
+/// Reflection data produced alongside the generated MSL source, letting a
+/// caller (e.g. a wgpu-style runtime) build argument-buffer layouts without
+/// re-parsing the emitted text.
+#[derive(Debug)]
+pub struct Reflection {
+    pub entry_points: FastHashMap<crate::Token<crate::Function>, EntryPointResources>,
+}
+
 pub struct Writer<W> {
     out: W,
 }
 
+/// Everything the expression/statement codegen needs to know about the
+/// function it's currently walking: which globals are bound as the entry
+/// point's `input`/`output` struct, and which of the (struct-typed) outputs
+/// got their built-ins lifted up to the root `output` struct.
+struct FunctionContext<'a> {
+    module: &'a crate::Module,
+    function: &'a crate::Function,
+    var_inputs: &'a FastHashSet<crate::Token<crate::GlobalVariable>>,
+    var_outputs: &'a FastHashSet<crate::Token<crate::GlobalVariable>>,
+    lifted_outputs: &'a FastHashMap<crate::Token<crate::GlobalVariable>, crate::Token<crate::StructDeclaration>>,
+    /// Compute kernels take their inputs as plain parameters rather than
+    /// through a `[[stage_in]]` struct, so references to them shouldn't be
+    /// rewritten to `input.<name>`.
+    flat_inputs: bool,
+}
+
+impl FunctionContext<'_> {
+    /// Best-effort type resolution for an expression, used to decide whether
+    /// an `AccessIndex` is a vector swizzle or a struct field. This is not a
+    /// full typifier: it only follows the handful of expression kinds that
+    /// can appear as the base of an access in generated code today.
+    fn type_of(&self, token: crate::Token<crate::Expression>) -> Option<&crate::Type> {
+        match self.function.expressions[token] {
+            crate::Expression::FunctionParameter(index) => {
+                self.function.parameter_types.get(index as usize)
+            }
+            crate::Expression::GlobalVariable(token) => {
+                // Every global's declared type is a pointer (see
+                // `TypedGlobalVariable::fmt`) - peel it to the pointee so
+                // callers see the actual vector/struct type being accessed.
+                match self.module.global_variables[token].ty {
+                    crate::Type::Pointer(pt) => Some(&self.module.complex_types.pointers[pt].base),
+                    ref other => Some(other),
+                }
+            }
+            crate::Expression::LocalVariable(token) => {
+                Some(&self.function.local_variables[token].ty)
+            }
+            crate::Expression::Load { pointer } => self.type_of(pointer),
+            crate::Expression::Access { base, .. } => self.type_of(base),
+            crate::Expression::Compose { ref ty, .. } => Some(ty),
+            _ => None,
+        }
+    }
+}
+
 fn scalar_kind_string(kind: crate::ScalarKind) -> &'static str {
     match kind {
         crate::ScalarKind::Float => "float",
@@ -319,11 +503,293 @@ fn vector_size_string(size: crate::VectorSize) -> &'static str {
     }
 }
 
+fn swizzle_component_char(index: u32) -> char {
+    match index {
+        0 => 'x',
+        1 => 'y',
+        2 => 'z',
+        _ => 'w',
+    }
+}
+
+fn binary_operator_string(op: crate::BinaryOperator) -> &'static str {
+    match op {
+        crate::BinaryOperator::Add => "+",
+        crate::BinaryOperator::Subtract => "-",
+        crate::BinaryOperator::Multiply => "*",
+        crate::BinaryOperator::Divide => "/",
+        crate::BinaryOperator::Modulo => "%",
+        crate::BinaryOperator::Equal => "==",
+        crate::BinaryOperator::NotEqual => "!=",
+        crate::BinaryOperator::Less => "<",
+        crate::BinaryOperator::LessEqual => "<=",
+        crate::BinaryOperator::Greater => ">",
+        crate::BinaryOperator::GreaterEqual => ">=",
+        crate::BinaryOperator::And => "&",
+        crate::BinaryOperator::Or => "|",
+        crate::BinaryOperator::Xor => "^",
+        crate::BinaryOperator::LogicalAnd => "&&",
+        crate::BinaryOperator::LogicalOr => "||",
+        crate::BinaryOperator::ShiftLeft => "<<",
+        crate::BinaryOperator::ShiftRight => ">>",
+    }
+}
+
+fn unary_operator_string(op: crate::UnaryOperator) -> &'static str {
+    match op {
+        crate::UnaryOperator::Negate => "-",
+        crate::UnaryOperator::Not => "!",
+    }
+}
+
 const NAME_INPUT: &'static str = "input";
 const NAME_OUTPUT: &'static str = "output";
 
+/// Whether some already-written input parameter resolves to the builtin
+/// attribute `name` (e.g. `"vertex_id"`, `"front_facing"`,
+/// `"thread_position_in_grid"`). Entry points append a trailing filler
+/// parameter to make Metal happy about the final comma, and that filler
+/// must not repeat an attribute a real input already claims - Metal
+/// rejects the same `[[attribute]]` on two parameters.
+fn attribute_already_claimed<'a>(
+    resolved: impl Iterator<Item = &'a ResolvedBinding>,
+    name: &str,
+) -> bool {
+    resolved.any(|binding| match *binding {
+        ResolvedBinding::BuiltIn(attr) => attr == name,
+        _ => false,
+    })
+}
+
+/// Collects every global variable referenced by `fun_token`, following
+/// `Expression::Call` edges so resources touched only inside a helper
+/// function are still attributed to the entry point that calls it.
+fn collect_used_globals(
+    module: &crate::Module,
+    fun_token: crate::Token<crate::Function>,
+    visited_functions: &mut FastHashSet<crate::Token<crate::Function>>,
+    globals: &mut Vec<crate::Token<crate::GlobalVariable>>,
+) {
+    if !visited_functions.insert(fun_token) {
+        return;
+    }
+    let fun = &module.functions[fun_token];
+    for (_, expr) in fun.expressions.iter() {
+        match *expr {
+            crate::Expression::GlobalVariable(token) => globals.push(token),
+            crate::Expression::Call { function, .. } => {
+                collect_used_globals(module, function, visited_functions, globals);
+            }
+            _ => {}
+        }
+    }
+}
+
 impl<W: Write> Writer<W> {
-    pub fn write(&mut self, module: &crate::Module, options: Options) -> Result<(), Error> {
+    fn put_indent(&mut self, level: usize) -> Result<(), Error> {
+        for _ in 0..level {
+            write!(self.out, "\t")?;
+        }
+        Ok(())
+    }
+
+    fn put_expression(
+        &mut self,
+        expr_token: crate::Token<crate::Expression>,
+        ctx: &FunctionContext,
+    ) -> Result<(), Error> {
+        match ctx.function.expressions[expr_token] {
+            crate::Expression::Access { base, index } => {
+                self.put_expression(base, ctx)?;
+                write!(self.out, "[")?;
+                self.put_expression(index, ctx)?;
+                write!(self.out, "]")?;
+            }
+            crate::Expression::AccessIndex { base, index } => {
+                self.put_expression(base, ctx)?;
+                match ctx.type_of(base) {
+                    Some(&crate::Type::Vector { .. }) => {
+                        write!(self.out, ".{}", swizzle_component_char(index))?;
+                    }
+                    Some(&crate::Type::Struct(token)) => {
+                        let decl = &ctx.module.complex_types.structs[token];
+                        let name = decl.members[index as usize]
+                            .name
+                            .or_index(MemberIndex(index as usize));
+                        write!(self.out, ".{}", name)?;
+                    }
+                    _ => {
+                        write!(self.out, "[{}]", index)?;
+                    }
+                }
+            }
+            crate::Expression::Compose { ref ty, ref components } => {
+                let placeholder = "";
+                let tv = TypedVar(ty, &placeholder, &ctx.module.complex_types);
+                write!(self.out, "{}(", tv)?;
+                for (i, &component) in components.iter().enumerate() {
+                    if i != 0 {
+                        write!(self.out, ", ")?;
+                    }
+                    self.put_expression(component, ctx)?;
+                }
+                write!(self.out, ")")?;
+            }
+            crate::Expression::FunctionParameter(index) => {
+                write!(self.out, "{}", Name::from(ParameterIndex(index as usize)))?;
+            }
+            crate::Expression::GlobalVariable(token) => {
+                let var = &ctx.module.global_variables[token];
+                let name = var.name.or_index(token);
+                if ctx.var_inputs.contains(&token) && ctx.flat_inputs {
+                    write!(self.out, "{}", name)?;
+                } else if ctx.var_inputs.contains(&token) {
+                    write!(self.out, "{}.{}", NAME_INPUT, name)?;
+                } else if ctx.var_outputs.contains(&token) {
+                    write!(self.out, "{}.{}", NAME_OUTPUT, name)?;
+                } else {
+                    write!(self.out, "{}", name)?;
+                }
+            }
+            crate::Expression::LocalVariable(token) => {
+                let name = ctx.function.local_variables[token].name.or_index(token);
+                write!(self.out, "{}", name)?;
+            }
+            crate::Expression::Load { pointer } => {
+                self.put_expression(pointer, ctx)?;
+            }
+            crate::Expression::Unary { op, expr } => {
+                write!(self.out, "{}(", unary_operator_string(op))?;
+                self.put_expression(expr, ctx)?;
+                write!(self.out, ")")?;
+            }
+            crate::Expression::Binary { op, left, right } => {
+                write!(self.out, "(")?;
+                self.put_expression(left, ctx)?;
+                write!(self.out, " {} ", binary_operator_string(op))?;
+                self.put_expression(right, ctx)?;
+                write!(self.out, ")")?;
+            }
+            crate::Expression::Call { function, ref arguments } => {
+                let name = ctx.module.functions[function].name.or_index(function);
+                write!(self.out, "{}(", name)?;
+                for (i, &arg) in arguments.iter().enumerate() {
+                    if i != 0 {
+                        write!(self.out, ", ")?;
+                    }
+                    self.put_expression(arg, ctx)?;
+                }
+                write!(self.out, ")")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn put_statement(
+        &mut self,
+        statement: &crate::Statement,
+        ctx: &FunctionContext,
+        is_entry_point: bool,
+        level: usize,
+    ) -> Result<(), Error> {
+        match *statement {
+            crate::Statement::Block(ref statements) => {
+                self.put_block(statements, ctx, is_entry_point, level)?;
+            }
+            crate::Statement::If { condition, ref accept, ref reject } => {
+                self.put_indent(level)?;
+                write!(self.out, "if (")?;
+                self.put_expression(condition, ctx)?;
+                writeln!(self.out, ") {{")?;
+                self.put_block(accept, ctx, is_entry_point, level + 1)?;
+                self.put_indent(level)?;
+                writeln!(self.out, "}}")?;
+                if !reject.is_empty() {
+                    self.put_indent(level)?;
+                    writeln!(self.out, "else {{")?;
+                    self.put_block(reject, ctx, is_entry_point, level + 1)?;
+                    self.put_indent(level)?;
+                    writeln!(self.out, "}}")?;
+                }
+            }
+            crate::Statement::Loop { ref body, ref continuing } => {
+                self.put_indent(level)?;
+                writeln!(self.out, "while (true) {{")?;
+                self.put_block(body, ctx, is_entry_point, level + 1)?;
+                self.put_block(continuing, ctx, is_entry_point, level + 1)?;
+                self.put_indent(level)?;
+                writeln!(self.out, "}}")?;
+            }
+            crate::Statement::Break => {
+                self.put_indent(level)?;
+                writeln!(self.out, "break;")?;
+            }
+            crate::Statement::Continue => {
+                self.put_indent(level)?;
+                writeln!(self.out, "continue;")?;
+            }
+            crate::Statement::Return { value } => {
+                self.put_indent(level)?;
+                if is_entry_point {
+                    writeln!(self.out, "return {};", NAME_OUTPUT)?;
+                } else if let Some(expr) = value {
+                    write!(self.out, "return ")?;
+                    self.put_expression(expr, ctx)?;
+                    writeln!(self.out, ";")?;
+                } else {
+                    writeln!(self.out, "return;")?;
+                }
+            }
+            crate::Statement::Kill => {
+                self.put_indent(level)?;
+                writeln!(self.out, "discard_fragment();")?;
+            }
+            crate::Statement::Store { pointer, value } => {
+                self.put_indent(level)?;
+                match ctx.function.expressions[pointer] {
+                    crate::Expression::GlobalVariable(token) if ctx.lifted_outputs.contains_key(&token) => {
+                        let struct_token = ctx.lifted_outputs[&token];
+                        let decl = &ctx.module.complex_types.structs[struct_token];
+                        // Re-evaluate `value` per member instead of caching it in a
+                        // local: expressions in this IR are pure, and a cached
+                        // `auto {name}_value` would collide if the same lifted
+                        // output is stored to more than once in one block.
+                        for (index, member) in decl.members.iter().enumerate() {
+                            if index != 0 {
+                                self.put_indent(level)?;
+                            }
+                            let member_name = member.name.or_index(MemberIndex(index));
+                            write!(self.out, "{}.{} = ", NAME_OUTPUT, member_name)?;
+                            self.put_expression(value, ctx)?;
+                            writeln!(self.out, ".{};", member_name)?;
+                        }
+                    }
+                    _ => {
+                        self.put_expression(pointer, ctx)?;
+                        write!(self.out, " = ")?;
+                        self.put_expression(value, ctx)?;
+                        writeln!(self.out, ";")?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn put_block(
+        &mut self,
+        statements: &[crate::Statement],
+        ctx: &FunctionContext,
+        is_entry_point: bool,
+        level: usize,
+    ) -> Result<(), Error> {
+        for statement in statements {
+            self.put_statement(statement, ctx, is_entry_point, level)?;
+        }
+        Ok(())
+    }
+
+    pub fn write(&mut self, module: &crate::Module, options: Options) -> Result<Reflection, Error> {
         writeln!(self.out, "#include <metal_stdlib>")?;
         writeln!(self.out, "#include <simd/simd.h>")?;
         writeln!(self.out, "using namespace metal;")?;
@@ -339,6 +805,7 @@ impl<W: Write> Writer<W> {
                 spirv::StorageClass::Input |
                 spirv::StorageClass::Output => continue,
                 spirv::StorageClass::Uniform => "constant",
+                spirv::StorageClass::Workgroup => "threadgroup",
                 other => {
                     log::warn!("Unexpected pointer class {:?}", other);
                     ""
@@ -372,46 +839,68 @@ impl<W: Write> Writer<W> {
         }
 
         // write down functions
-        let mut uniforms_used = FastHashSet::default();
+        let mut entry_points = FastHashMap::default();
         writeln!(self.out, "")?;
         for (fun_token, fun) in module.functions.iter() {
+            // Each function gets its own parameter list, so a uniform
+            // referenced by more than one function (e.g. a vertex and a
+            // fragment entry point sharing a buffer) must be re-added here
+            // rather than deduplicated across the whole module.
+            let mut uniforms_used = FastHashSet::default();
             let mut exec_model = None;
             let mut var_inputs = FastHashSet::default();
             let mut var_outputs = FastHashSet::default();
+            let mut lifted_outputs = FastHashMap::default();
+            let mut ep_inputs = FastHashMap::default();
+            let mut ep_outputs = FastHashMap::default();
+            let mut local_size = [1 as spirv::Word; 3];
             for ep in module.entry_points.iter() {
                 if ep.function == fun_token {
                     var_inputs.extend(ep.inputs.iter().cloned());
                     var_outputs.extend(ep.outputs.iter().cloned());
+                    if ep.exec_model == spirv::ExecutionModel::GLCompute {
+                        local_size = ep.local_size;
+                    }
                     if exec_model.is_some() {
                         if exec_model != Some(ep.exec_model) {
-                            return Err(Error::MixedExecutionModels(fun_token));
+                            return Err(Error::MixedExecutionModels {
+                                function: fun.name.or_index(fun_token).to_string(),
+                                first: exec_model.unwrap(),
+                                second: ep.exec_model,
+                            });
                         }
                     } else {
                         exec_model = Some(ep.exec_model);
                     }
                 }
             }
+            // Metal kernels take thread/threadgroup built-ins as plain parameters,
+            // never as members of a `[[stage_in]]` struct.
+            let is_compute = exec_model == Some(spirv::ExecutionModel::GLCompute);
             let input_name = fun.name.or_index(InputStructIndex(fun_token));
             let output_name = fun.name.or_index(OutputStructIndex(fun_token));
             if let Some(em) = exec_model {
-                writeln!(self.out, "struct {} {{", input_name)?;
                 let (em_str, in_mode, out_mode) = match em {
                     spirv::ExecutionModel::Vertex => ("vertex", LocationMode::VertexInput, LocationMode::Intermediate),
                     spirv::ExecutionModel::Fragment => ("fragment", LocationMode::Intermediate, LocationMode::FragmentOutput),
-                    spirv::ExecutionModel::GLCompute => ("compute", LocationMode::Intermediate, LocationMode::Intermediate),
+                    spirv::ExecutionModel::GLCompute => ("kernel", LocationMode::Intermediate, LocationMode::Intermediate),
                     _ => return Err(Error::UnsupportedExecutionModel(em)),
                 };
-                for &token in var_inputs.iter() {
-                    let var = &module.global_variables[token];
-                    let tyvar = TypedGlobalVariable { module, token };
-                    write!(self.out, "\t{}", tyvar)?;
-                    if let Some(ref binding) = var.binding {
-                        let resolved = options.resolve_binding(binding, in_mode)?;
-                        write!(self.out, " [[{}]]", resolved)?;
+                if !is_compute {
+                    writeln!(self.out, "struct {} {{", input_name)?;
+                    for &token in var_inputs.iter() {
+                        let var = &module.global_variables[token];
+                        let tyvar = TypedGlobalVariable { module, token };
+                        write!(self.out, "\t{}", tyvar)?;
+                        if let Some(ref binding) = var.binding {
+                            let resolved = options.resolve_binding(binding, in_mode)?;
+                            ep_inputs.insert(token, resolved.clone());
+                            write!(self.out, " [[{}]]", resolved)?;
+                        }
+                        writeln!(self.out, ";")?;
                     }
-                    writeln!(self.out, ";")?;
+                    writeln!(self.out, "}};")?;
                 }
-                writeln!(self.out, "}};")?;
                 writeln!(self.out, "struct {} {{", output_name)?;
                 for &token in var_outputs.iter() {
                     let var = &module.global_variables[token];
@@ -424,10 +913,14 @@ impl<W: Write> Writer<W> {
                                 let tv = TypedVar(&member.ty, &name, &module.complex_types);
                                 let binding = member.binding
                                     .as_ref()
-                                    .ok_or(Error::MissingBinding(token))?;
+                                    .ok_or_else(|| Error::MissingBinding {
+                                        global: var.name.or_index(token).to_string(),
+                                        member: name.to_string(),
+                                    })?;
                                 let resolved = options.resolve_binding(binding, out_mode)?;
                                 writeln!(self.out, "\t{} [[{}]];", tv, resolved)?;
                             }
+                            lifted_outputs.insert(token, st);
                             continue
                         }
                     }
@@ -435,6 +928,7 @@ impl<W: Write> Writer<W> {
                     write!(self.out, "\t{}", tyvar)?;
                     if let Some(ref binding) = var.binding {
                         let resolved = options.resolve_binding(binding, out_mode)?;
+                        ep_outputs.insert(token, resolved.clone());
                         write!(self.out, " [[{}]]", resolved)?;
                     }
                     writeln!(self.out, ";")?;
@@ -445,8 +939,29 @@ impl<W: Write> Writer<W> {
 
             let fun_name = fun.name.or_index(fun_token);
             if exec_model.is_some() {
-                writeln!(self.out, "{} {}(", output_name, fun_name)?;
-                writeln!(self.out, "\t{} {} [[stage_in]],", input_name, NAME_INPUT)?;
+                // Metal requires `kernel`-qualified functions to return
+                // `void`; a compute entry point has no output struct to
+                // hand back (results are written through bound buffers).
+                if is_compute {
+                    writeln!(self.out, "void {}(", fun_name)?;
+                } else {
+                    writeln!(self.out, "{} {}(", output_name, fun_name)?;
+                }
+                if is_compute {
+                    for &token in var_inputs.iter() {
+                        let var = &module.global_variables[token];
+                        let tyvar = TypedGlobalVariable { module, token };
+                        write!(self.out, "\t{}", tyvar)?;
+                        if let Some(ref binding) = var.binding {
+                            let resolved = options.resolve_binding(binding, LocationMode::Intermediate)?;
+                            ep_inputs.insert(token, resolved.clone());
+                            write!(self.out, " [[{}]]", resolved)?;
+                        }
+                        writeln!(self.out, ",")?;
+                    }
+                } else {
+                    writeln!(self.out, "\t{} {} [[stage_in]],", input_name, NAME_INPUT)?;
+                }
             } else {
                 let fun_tv = TypedVar(&fun.return_type, &fun_name, &module.complex_types);
                 writeln!(self.out, "{}(", fun_tv)?;
@@ -459,40 +974,178 @@ impl<W: Write> Writer<W> {
             for (_, expr) in fun.expressions.iter() {
                 if let crate::Expression::GlobalVariable(token) = *expr {
                     let var = &module.global_variables[token];
-                    if var.class == spirv::StorageClass::Uniform && !uniforms_used.contains(&token) {
+                    let is_buffer_class = var.class == spirv::StorageClass::Uniform
+                        || (is_compute && var.class == spirv::StorageClass::Workgroup);
+                    if is_buffer_class && !uniforms_used.contains(&token) {
                         uniforms_used.insert(token);
                         let var = TypedGlobalVariable { module, token };
                         writeln!(self.out, "\t{},", var)?;
                     }
                 }
             }
-            // add an extra parameter to make Metal happy about the comma
+            // Reflection needs every resource the entry point can reach, not
+            // just the ones it touches directly - walk the call graph so a
+            // descriptor bound only inside a helper function still shows up.
+            let mut resources = Vec::new();
+            let mut resources_seen = FastHashSet::default();
+            let mut reachable_globals = Vec::new();
+            let mut visited_functions = FastHashSet::default();
+            collect_used_globals(module, fun_token, &mut visited_functions, &mut reachable_globals);
+            for token in reachable_globals {
+                let var = &module.global_variables[token];
+                if let Some(crate::Binding::Descriptor { set, binding }) = var.binding {
+                    let source = BindSource { set, binding };
+                    if resources_seen.insert(source.clone()) {
+                        match options.resolve_binding(&crate::Binding::Descriptor { set, binding }, LocationMode::Intermediate)? {
+                            ResolvedBinding::Resource(target) => resources.push((source, target)),
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            // add an extra parameter to make Metal happy about the comma,
+            // unless a real input already claims the filler's attribute -
+            // Metal rejects the same `[[attribute]]` on two parameters.
             match exec_model {
                 Some(spirv::ExecutionModel::Vertex) => {
-                    writeln!(self.out, "\tunsigned _dummy [[vertex_id]]")?;
+                    if attribute_already_claimed(ep_inputs.values(), "vertex_id") {
+                        writeln!(self.out, "\tunsigned _dummy")?;
+                    } else {
+                        writeln!(self.out, "\tunsigned _dummy [[vertex_id]]")?;
+                    }
                 }
                 Some(spirv::ExecutionModel::Fragment) => {
-                    writeln!(self.out, "\tbool _dummy [[front_facing]]")?;
+                    if attribute_already_claimed(ep_inputs.values(), "front_facing") {
+                        writeln!(self.out, "\tbool _dummy")?;
+                    } else {
+                        writeln!(self.out, "\tbool _dummy [[front_facing]]")?;
+                    }
                 }
                 Some(spirv::ExecutionModel::GLCompute) => {
-                    writeln!(self.out, "\tunsigned _dummy [[threads_per_grid]]")?;
+                    if attribute_already_claimed(ep_inputs.values(), "thread_position_in_grid") {
+                        writeln!(self.out, "\tint _dummy")?;
+                    } else {
+                        writeln!(self.out, "\tuint3 _thread_position_in_grid [[thread_position_in_grid]]")?;
+                    }
                 }
                 _ => {
                     writeln!(self.out, "\tint _dummy")?;
                 }
             }
-            writeln!(self.out, ") {{")?;
-            writeln!(self.out, "\t{} {};", output_name, NAME_OUTPUT)?;
-            writeln!(self.out, "\treturn {};", NAME_OUTPUT)?;
+            if is_compute {
+                let total_threads = local_size[0] * local_size[1] * local_size[2];
+                writeln!(self.out, ") [[max_total_threads_per_threadgroup({})]] {{", total_threads)?;
+            } else {
+                writeln!(self.out, ") {{")?;
+            }
+            // Compute entry points are `void`-returning, so they never
+            // declare or hand back a `NAME_OUTPUT` local.
+            let is_entry_point = exec_model.is_some() && !is_compute;
+            if is_entry_point {
+                writeln!(self.out, "\t{} {};", output_name, NAME_OUTPUT)?;
+            }
+            let ctx = FunctionContext {
+                module,
+                function: fun,
+                var_inputs: &var_inputs,
+                var_outputs: &var_outputs,
+                lifted_outputs: &lifted_outputs,
+                flat_inputs: is_compute,
+            };
+            for (token, local) in fun.local_variables.iter() {
+                let name = local.name.or_index(token);
+                let tv = TypedVar(&local.ty, &name, &module.complex_types);
+                write!(self.out, "\t{}", tv)?;
+                if let Some(init) = local.init {
+                    write!(self.out, " = ")?;
+                    self.put_expression(init, &ctx)?;
+                }
+                writeln!(self.out, ";")?;
+            }
+            self.put_block(&fun.body, &ctx, is_entry_point, 1)?;
+            if is_entry_point {
+                writeln!(self.out, "\treturn {};", NAME_OUTPUT)?;
+            }
             writeln!(self.out, "}}")?;
+
+            if let Some(execution_model) = exec_model {
+                entry_points.insert(fun_token, EntryPointResources {
+                    execution_model,
+                    resources,
+                    inputs: ep_inputs,
+                    outputs: ep_outputs,
+                });
+            }
         }
 
-        Ok(())
+        Ok(Reflection { entry_points })
     }
 }
 
-pub fn write_string(module: &crate::Module, options: Options) -> Result<String, Error> {
+pub fn write_string(module: &crate::Module, options: Options) -> Result<(String, Reflection), Error> {
     let mut w = Writer { out: String::new() };
-    w.write(module, options)?;
-    Ok(w.out)
+    let reflection = w.write(module, options)?;
+    Ok((w.out, reflection))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn swizzle_components_cover_all_four_lanes() {
+        assert_eq!(swizzle_component_char(0), 'x');
+        assert_eq!(swizzle_component_char(1), 'y');
+        assert_eq!(swizzle_component_char(2), 'z');
+        assert_eq!(swizzle_component_char(3), 'w');
+    }
+
+    #[test]
+    fn binary_operators_match_msl_spelling() {
+        assert_eq!(binary_operator_string(crate::BinaryOperator::Add), "+");
+        assert_eq!(binary_operator_string(crate::BinaryOperator::LogicalAnd), "&&");
+        assert_eq!(binary_operator_string(crate::BinaryOperator::ShiftRight), ">>");
+    }
+
+    #[test]
+    fn unary_operators_match_msl_spelling() {
+        assert_eq!(unary_operator_string(crate::UnaryOperator::Negate), "-");
+        assert_eq!(unary_operator_string(crate::UnaryOperator::Not), "!");
+    }
+
+    #[test]
+    fn bind_source_dedups_by_set_and_binding() {
+        // `resources_seen` (used when collecting reflection data across a
+        // call graph) relies on `BindSource` hashing/equality by value.
+        let mut seen = FastHashSet::default();
+        assert!(seen.insert(BindSource { set: 0, binding: 1 }));
+        assert!(!seen.insert(BindSource { set: 0, binding: 1 }));
+        assert!(seen.insert(BindSource { set: 0, binding: 2 }));
+    }
+
+    #[test]
+    fn global_invocation_id_resolves_to_thread_position_in_grid() {
+        // The duplicate-filler-parameter guard in `write` matches on this
+        // exact string, so it must stay in lockstep with the builtin table.
+        assert_eq!(
+            resolve_builtin_name(spirv::BuiltIn::GlobalInvocationId, LocationMode::Intermediate).unwrap(),
+            "thread_position_in_grid",
+        );
+    }
+
+    #[test]
+    fn filler_parameter_skipped_when_builtin_already_claimed() {
+        // Checks the lookup `write` uses to decide whether its trailing
+        // filler parameter needs an attribute at all: a real input claiming
+        // `vertex_id` is found, while `front_facing` and
+        // `thread_position_in_grid`, which no input here claims, are not.
+        let claimed = [
+            ResolvedBinding::BuiltIn("vertex_id"),
+            ResolvedBinding::Attribute(0),
+        ];
+        assert!(attribute_already_claimed(claimed.iter(), "vertex_id"));
+        assert!(!attribute_already_claimed(claimed.iter(), "front_facing"));
+        assert!(!attribute_already_claimed(claimed.iter(), "thread_position_in_grid"));
+        assert!(!attribute_already_claimed(std::iter::empty::<&ResolvedBinding>(), "vertex_id"));
+    }
 }
\ No newline at end of file